@@ -1,18 +1,231 @@
 // Copyright 2018-2020 the Deno authors. All rights reserved. MIT license.
 
-/// This module is meant to eventually implement HTTP cache
-/// as defined in RFC 7234 (https://tools.ietf.org/html/rfc7234).
-/// Currently it's a very simplified version to fulfill Deno needs
-/// at hand.
+/// This module implements the HTTP cache semantics defined in
+/// RFC 7234 (https://tools.ietf.org/html/rfc7234), covering freshness
+/// computation and revalidation triggers for cached responses.
 use crate::fs as deno_fs;
 use crate::http_util::HeadersMap;
+use chrono::DateTime;
+use chrono::Utc;
 use deno_core::ErrBox;
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 use url::Url;
 
+/// The outcome of checking a cached response's freshness against the
+/// current time, per RFC 7234 section 4.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+  /// The cached response is within its freshness lifetime and may be
+  /// used without contacting the origin server.
+  Fresh,
+  /// The cached response has exceeded its freshness lifetime and should
+  /// be revalidated before use, but may be served stale if revalidation
+  /// is not possible (e.g. the origin is unreachable).
+  Stale,
+  /// The cached response carries `no-cache`, `no-store` or a stale
+  /// `must-revalidate`/`proxy-revalidate` directive, so it must not be
+  /// used without a successful revalidation.
+  MustRevalidate,
+}
+
+/// Heuristic freshness fraction applied to `Date - Last-Modified` when
+/// neither `max-age` nor `Expires` is present, per RFC 7234 section 4.2.2.
+const HEURISTIC_FRESHNESS_FRACTION: f64 = 0.1;
+
+fn header<'a>(headers: &'a HeadersMap, name: &str) -> Option<&'a str> {
+  headers.get(name).map(|v| v.as_str())
+}
+
+fn cache_control_directives(headers: &HeadersMap) -> Vec<String> {
+  header(headers, "cache-control")
+    .map(|v| v.split(',').map(|d| d.trim().to_lowercase()).collect())
+    .unwrap_or_else(Vec::new)
+}
+
+fn cache_control_max_age(headers: &HeadersMap) -> Option<i64> {
+  cache_control_directives(headers).iter().find_map(|d| {
+    d.strip_prefix("max-age=")
+      .and_then(|secs| secs.parse::<i64>().ok())
+  })
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+  DateTime::parse_from_rfc2822(value)
+    .map(|dt| dt.with_timezone(&Utc))
+    .ok()
+}
+
+/// Returned by `HttpCache::get` when the cached body no longer matches
+/// the digest recorded at `set()` time. The offending entry is deleted
+/// before this is returned, so the caller's next `set()` starts clean.
+#[derive(Debug)]
+pub struct CorruptedCacheError {
+  pub url: Url,
+}
+
+impl std::fmt::Display for CorruptedCacheError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "cached content for \"{}\" failed integrity verification and was purged from the cache",
+      self.url
+    )
+  }
+}
+
+impl std::error::Error for CorruptedCacheError {}
+
+/// Returned by `HttpCache::set` when the response body doesn't match a
+/// `Digest` or SRI-style `Integrity` header the server sent, so the
+/// response is refused rather than cached.
+#[derive(Debug)]
+pub struct IntegrityMismatchError {
+  pub url: Url,
+}
+
+impl std::fmt::Display for IntegrityMismatchError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(
+      f,
+      "refusing to cache \"{}\": response body doesn't match its Digest/Integrity header",
+      self.url
+    )
+  }
+}
+
+impl std::error::Error for IntegrityMismatchError {}
+
+fn sha256_hex(content: &[u8]) -> String {
+  crate::checksum::gen(vec![content])
+}
+
+// Decodes a base64 digest (from a `Digest` or `Integrity` header) into
+// the same lowercase hex form `sha256_hex` produces, so the two can be
+// compared directly.
+fn base64_digest_to_hex(value: &str) -> Option<String> {
+  let bytes = base64::decode(value).ok()?;
+  Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Checks `content` against any server-declared `Digest` (RFC 3230) or
+// SRI-style `Integrity` header naming a sha-256 digest. Headers naming
+// other algorithms, or missing entirely, are treated as "nothing to
+// verify" rather than an error.
+fn verify_server_integrity(
+  url: &Url,
+  headers_map: &HeadersMap,
+  content: &[u8],
+) -> Result<(), ErrBox> {
+  let declared = header(headers_map, "integrity")
+    .and_then(|v| v.strip_prefix("sha256-"))
+    .or_else(|| {
+      header(headers_map, "digest").and_then(|v| {
+        v.split(',').find_map(|part| {
+          let part = part.trim();
+          part
+            .strip_prefix("sha-256=")
+            .or_else(|| part.strip_prefix("SHA-256="))
+        })
+      })
+    });
+
+  let expected_hex = match declared.and_then(base64_digest_to_hex) {
+    Some(hex) => hex,
+    None => return Ok(()),
+  };
+
+  if expected_hex == sha256_hex(content) {
+    Ok(())
+  } else {
+    Err(
+      IntegrityMismatchError {
+        url: url.to_owned(),
+      }
+      .into(),
+    )
+  }
+}
+
+/// The lowercased request header names named by a response's `Vary`
+/// header, or an empty `Vec` if the response doesn't vary (or varies on
+/// `*`, which we can never match and so don't bother tracking).
+fn vary_header_names(headers_map: &HeadersMap) -> Vec<String> {
+  match header(headers_map, "vary") {
+    Some(vary) if vary.trim() == "*" => Vec::new(),
+    Some(vary) => vary
+      .split(',')
+      .map(|name| name.trim().to_lowercase())
+      .filter(|name| !name.is_empty())
+      .collect(),
+    None => Vec::new(),
+  }
+}
+
+// Hashes `selecting_headers` into a stable suffix for `variant_filename`.
+// Sorts by header name first so the result doesn't depend on a
+// `HashMap`'s (per-process-random) iteration order - unlike hashing
+// `serde_json::to_string` of the map directly, this is reproducible
+// across runs for the same logical variant.
+fn variant_suffix(selecting_headers: &HeadersMap) -> String {
+  let mut pairs: Vec<(&str, &str)> = selecting_headers
+    .iter()
+    .map(|(k, v)| (k.as_str(), v.as_str()))
+    .collect();
+  pairs.sort_unstable_by_key(|(name, _)| *name);
+  let canonical = pairs
+    .into_iter()
+    .map(|(name, value)| format!("{}\u{0}{}", name, value))
+    .collect::<Vec<_>>()
+    .join("\u{1}");
+  crate::checksum::gen(vec![canonical.as_bytes()])
+}
+
+/// Projects `request_headers` down to the subset named by `vary_names`,
+/// normalizing both header name and value so that equivalent requests
+/// select the same cached variant.
+fn select_vary_headers(
+  vary_names: &[String],
+  request_headers: &HeadersMap,
+) -> HeadersMap {
+  vary_names
+    .iter()
+    .map(|name| {
+      let value = request_headers
+        .iter()
+        .find(|(k, _)| k.to_lowercase() == *name)
+        .map(|(_, v)| v.trim().to_lowercase())
+        .unwrap_or_default();
+      (name.clone(), value)
+    })
+    .collect()
+}
+
+/// On-disk index of the variants cached for a single URL, keyed by the
+/// request header values that selected each one (per its `Vary` header).
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+struct VariantIndex {
+  variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Variant {
+  selecting_headers: HeadersMap,
+  // Suffix appended to the URL's hashed filename to disambiguate this
+  // variant's content/headers files on disk.
+  suffix: String,
+}
+
 /// Turn base of url (scheme, hostname, port) into a valid filename.
 /// This method replaces port part with a special string token (because
 /// ":" cannot be used in filename on some platforms).
@@ -65,55 +278,725 @@ pub fn url_to_filename(url: &Url) -> PathBuf {
   cache_filename
 }
 
+fn now_millis() -> i64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as i64)
+    .unwrap_or(0)
+}
+
+// Monotonic counter used to break ties between accesses that land in the
+// same millisecond, so LRU order stays deterministic under fast, bursty
+// access patterns (e.g. warming several entries back-to-back in tests).
+static ACCESS_SEQ: std::sync::atomic::AtomicU64 =
+  std::sync::atomic::AtomicU64::new(0);
+
+fn next_access_seq() -> u64 {
+  ACCESS_SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+// Minimum interval between `record_access`'s throttled writes of the
+// in-memory index to disk. Keeps `get()` (the hottest path) from hitting
+// the filesystem on every call while still bounding how stale the
+// on-disk `accessed` times can get for a reader, like `prune()`, that
+// runs in a fresh process.
+const ACCESS_FLUSH_INTERVAL_MS: i64 = 60_000;
+
+/// Size and last-access bookkeeping for a single cached entry, used by
+/// `HttpCache`'s LRU eviction.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+struct IndexEntry {
+  size: u64,
+  // Milliseconds since the Unix epoch.
+  accessed: i64,
+  // Tiebreaker for entries accessed within the same millisecond.
+  accessed_seq: u64,
+}
+
+/// In-memory (and, incrementally, on-disk) index of every entry in an
+/// `HttpCache`, keyed by the entry's content filename relative to the
+/// cache's `location`. Loaded lazily on first use so that opening a
+/// cache with no eviction in play stays cheap.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+struct CacheIndex {
+  entries: HashMap<PathBuf, IndexEntry>,
+}
+
+impl CacheIndex {
+  fn total_bytes(&self) -> u64 {
+    self.entries.values().map(|e| e.size).sum()
+  }
+}
+
+// Writes `data` to `filename` atomically: the bytes are written to a
+// temporary file in the same directory (so the following rename stays
+// on one filesystem) and then renamed into place, so a concurrent
+// reader never observes a partially-written file. Since `$DENO_DIR` may
+// be shared by multiple Deno processes, this matters for correctness,
+// not just crash-safety.
+fn write_file_atomic(filename: &Path, data: &[u8]) -> Result<(), ErrBox> {
+  let parent = filename
+    .parent()
+    .expect("Cache filename should have a parent dir");
+  let tmp_filename = parent.join(format!(
+    "{}.{}.tmp",
+    filename.file_name().unwrap().to_string_lossy(),
+    std::process::id()
+  ));
+  deno_fs::write_file(&tmp_filename, data, 0o666)?;
+  fs::rename(&tmp_filename, filename)?;
+  Ok(())
+}
+
+// Appends a variant suffix to a base cache filename so that a single URL
+// can have more than one cached body on disk (see `VariantIndex`).
+fn variant_filename(base_filename: &Path, suffix: &str) -> PathBuf {
+  let mut file_name = base_filename.file_name().unwrap().to_owned();
+  file_name.push(".");
+  file_name.push(suffix);
+  base_filename.with_file_name(file_name)
+}
+
+// Removes the `Variant` entry (if any) whose content lives at
+// `full_path` from its URL's `variants.json` sidecar, deleting the
+// sidecar entirely if that was its last entry. Called whenever eviction
+// or pruning deletes a variant's content file directly (bypassing
+// `HttpCache::set`), so `resolve_cache_filename` doesn't keep "matching"
+// requests to a variant whose content no longer exists.
+fn remove_variant_entry(full_path: &Path) -> Result<(), ErrBox> {
+  let base_path = full_path.with_file_name(
+    full_path
+      .file_stem()
+      .unwrap_or_else(|| full_path.file_name().unwrap()),
+  );
+  let index_filename = base_path.with_extension("variants.json");
+  if !index_filename.exists() {
+    return Ok(());
+  }
+
+  let mut index: VariantIndex =
+    serde_json::from_str(&fs::read_to_string(&index_filename)?)?;
+  let before = index.variants.len();
+  index
+    .variants
+    .retain(|v| variant_filename(&base_path, &v.suffix) != full_path);
+  if index.variants.len() == before {
+    return Ok(());
+  }
+
+  if index.variants.is_empty() {
+    let _ = fs::remove_file(&index_filename);
+  } else {
+    let serialized = serde_json::to_string(&index)?;
+    write_file_atomic(&index_filename, serialized.as_bytes())?;
+  }
+  Ok(())
+}
+
+// Derives the headers sidecar path for `cache_filename` by appending
+// ".headers.json" rather than using `Path::with_extension`, which
+// replaces everything after the *last* dot - and a variant's content
+// filename (`<hash>.<suffix>`) already has one, so `with_extension`
+// would collapse every variant of a URL onto the same headers file.
+fn headers_filename(cache_filename: &Path) -> PathBuf {
+  let mut file_name = cache_filename.as_os_str().to_owned();
+  file_name.push(".headers.json");
+  PathBuf::from(file_name)
+}
+
+// Derives the sidecar path `set()`/`get()` use to store the sha-256
+// digest of a cached body, the same way `headers_filename` derives the
+// headers sidecar - kept out of the headers file (and so out of the
+// `HeadersMap` callers see) so it can never collide with, or be
+// silently clobbered by, a header the origin server actually sent.
+fn integrity_filename(cache_filename: &Path) -> PathBuf {
+  let mut file_name = cache_filename.as_os_str().to_owned();
+  file_name.push(".integrity");
+  PathBuf::from(file_name)
+}
+
+// Deletes a cached entry's content file and its headers/integrity
+// sidecars. Used by every path that removes an entry's files directly
+// (bypassing `HttpCache::set`) - eviction, pruning, and corruption
+// cleanup - so they can't drift out of sync with which sidecars exist.
+// Best-effort: a sidecar that's already gone (or never existed) isn't
+// an error.
+fn delete_cache_files(cache_filename: &Path) {
+  let _ = fs::remove_file(cache_filename);
+  let _ = fs::remove_file(headers_filename(cache_filename));
+  let _ = fs::remove_file(integrity_filename(cache_filename));
+}
+
 #[derive(Clone)]
 pub struct HttpCache {
   pub location: PathBuf,
+  max_size_bytes: Option<u64>,
+  index: Arc<Mutex<Option<CacheIndex>>>,
+  // Milliseconds since the Unix epoch at which `record_access` last
+  // persisted the index to disk. See `ACCESS_FLUSH_INTERVAL_MS`.
+  last_flush_millis: Arc<std::sync::atomic::AtomicI64>,
 }
 
 impl HttpCache {
   /// Returns error if unable to create directory
-  /// at specified location.
-  pub fn new(location: &Path) -> Result<Self, ErrBox> {
+  /// at specified location. `max_size_bytes`, if set, bounds the total
+  /// size of cached response bodies; once exceeded, the least-recently-
+  /// used entries are evicted after each `set()` call.
+  pub fn new(
+    location: &Path,
+    max_size_bytes: Option<u64>,
+  ) -> Result<Self, ErrBox> {
     fs::create_dir_all(&location)?;
     Ok(Self {
       location: location.to_owned(),
+      max_size_bytes,
+      index: Arc::new(Mutex::new(None)),
+      last_flush_millis: Arc::new(std::sync::atomic::AtomicI64::new(0)),
     })
   }
 
+  fn index_filename(&self) -> PathBuf {
+    self.location.join("cache_index.json")
+  }
+
+  fn load_index(&self) -> Result<(), ErrBox> {
+    let mut guard = self.index.lock().unwrap();
+    if guard.is_none() {
+      let index_filename = self.index_filename();
+      let index = if index_filename.exists() {
+        serde_json::from_str(&fs::read_to_string(&index_filename)?)?
+      } else {
+        CacheIndex::default()
+      };
+      *guard = Some(index);
+    }
+    Ok(())
+  }
+
+  fn persist_index(&self, index: &CacheIndex) -> Result<(), ErrBox> {
+    let serialized = serde_json::to_string(index)?;
+    write_file_atomic(&self.index_filename(), serialized.as_bytes())
+  }
+
+  // The index key for `cache_filename`: its path relative to `location`.
+  fn rel_path(&self, cache_filename: &Path) -> PathBuf {
+    cache_filename
+      .strip_prefix(&self.location)
+      .unwrap_or(cache_filename)
+      .to_owned()
+  }
+
+  // Records `size` and the current time as the last access for the
+  // entry at `cache_filename` (relative to `location`), and persists
+  // the index. Called after every `set()`. This write counts as a flush
+  // for `record_access`'s throttle.
+  fn touch_entry(&self, cache_filename: &Path, size: u64) -> Result<(), ErrBox> {
+    let rel_path = self.rel_path(cache_filename);
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let index = guard.as_mut().unwrap();
+    index.entries.insert(
+      rel_path,
+      IndexEntry {
+        size,
+        accessed: now_millis(),
+        accessed_seq: next_access_seq(),
+      },
+    );
+    let snapshot = index.clone();
+    drop(guard);
+    self.persist_index(&snapshot)?;
+    self
+      .last_flush_millis
+      .store(now_millis(), std::sync::atomic::Ordering::Relaxed);
+    Ok(())
+  }
+
+  // Updates the last-access time of an existing entry (keeping its
+  // recorded size, or computing it from disk if this is the first time
+  // the entry has been indexed). Called on every `get()`.
+  // Updates the in-memory index on every call, since this runs on every
+  // `get()`, the hottest path in the cache (once per module resolution).
+  // The update is only *persisted* to disk at most once every
+  // `ACCESS_FLUSH_INTERVAL_MS` - a caller that reads far more often than
+  // it writes would otherwise never durably record which entries are
+  // actually hot, and a fresh process calling `prune()` could delete
+  // entries that are in constant use. An explicit `flush_index()` call
+  // still persists immediately, bypassing the throttle.
+  fn record_access(&self, cache_filename: &Path) -> Result<(), ErrBox> {
+    let rel_path = self.rel_path(cache_filename);
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let index = guard.as_mut().unwrap();
+    let size = index
+      .entries
+      .get(&rel_path)
+      .map(|e| e.size)
+      .unwrap_or_else(|| {
+        fs::metadata(cache_filename).map(|m| m.len()).unwrap_or(0)
+      });
+    index.entries.insert(
+      rel_path,
+      IndexEntry {
+        size,
+        accessed: now_millis(),
+        accessed_seq: next_access_seq(),
+      },
+    );
+
+    let now = now_millis();
+    let last_flush =
+      self.last_flush_millis.load(std::sync::atomic::Ordering::Relaxed);
+    if now.saturating_sub(last_flush) >= ACCESS_FLUSH_INTERVAL_MS {
+      let snapshot = index.clone();
+      drop(guard);
+      self.persist_index(&snapshot)?;
+      self
+        .last_flush_millis
+        .store(now, std::sync::atomic::Ordering::Relaxed);
+    }
+    Ok(())
+  }
+
+  /// Persists any in-memory access-time updates (accumulated by `get()`
+  /// calls since the last write) to the on-disk index. Callers that read
+  /// from the cache far more often than they write to it may want to
+  /// call this periodically so access times survive a restart.
+  pub fn flush_index(&self) -> Result<(), ErrBox> {
+    self.load_index()?;
+    let guard = self.index.lock().unwrap();
+    let snapshot = guard.as_ref().unwrap().clone();
+    drop(guard);
+    self.persist_index(&snapshot)?;
+    self
+      .last_flush_millis
+      .store(now_millis(), std::sync::atomic::Ordering::Relaxed);
+    Ok(())
+  }
+
+  // Evicts least-recently-used entries until the cache's total recorded
+  // size is at or under `max_size_bytes`. A no-op if no bound was given
+  // to `new()`.
+  fn evict_if_needed(&self) -> Result<(), ErrBox> {
+    let max_size_bytes = match self.max_size_bytes {
+      Some(max) => max,
+      None => return Ok(()),
+    };
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let index = guard.as_mut().unwrap();
+    let mut total = index.total_bytes();
+    if total <= max_size_bytes {
+      return Ok(());
+    }
+
+    let mut entries: Vec<(PathBuf, IndexEntry)> =
+      index.entries.clone().into_iter().collect();
+    entries
+      .sort_by_key(|(_, entry)| (entry.accessed, entry.accessed_seq));
+
+    for (rel_path, entry) in entries {
+      if total <= max_size_bytes {
+        break;
+      }
+      let full_path = self.location.join(&rel_path);
+      delete_cache_files(&full_path);
+      let _ = remove_variant_entry(&full_path);
+      index.entries.remove(&rel_path);
+      total = total.saturating_sub(entry.size);
+    }
+
+    let snapshot = index.clone();
+    drop(guard);
+    self.persist_index(&snapshot)
+  }
+
+  /// Removes every entry from the cache, including the eviction index.
+  pub fn clear(&self) -> Result<(), ErrBox> {
+    if self.location.is_dir() {
+      fs::remove_dir_all(&self.location)?;
+    }
+    fs::create_dir_all(&self.location)?;
+    let mut guard = self.index.lock().unwrap();
+    *guard = Some(CacheIndex::default());
+    Ok(())
+  }
+
+  /// Removes entries that haven't been accessed within `max_age`.
+  pub fn prune(&self, max_age: chrono::Duration) -> Result<(), ErrBox> {
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let index = guard.as_mut().unwrap();
+    let cutoff = now_millis() - max_age.num_milliseconds().max(0);
+
+    let stale: Vec<PathBuf> = index
+      .entries
+      .iter()
+      .filter(|(_, entry)| entry.accessed < cutoff)
+      .map(|(rel_path, _)| rel_path.clone())
+      .collect();
+
+    for rel_path in &stale {
+      let full_path = self.location.join(rel_path);
+      delete_cache_files(&full_path);
+      let _ = remove_variant_entry(&full_path);
+      index.entries.remove(rel_path);
+    }
+
+    let snapshot = index.clone();
+    drop(guard);
+    self.persist_index(&snapshot)
+  }
+
   pub(crate) fn get_cache_filename(&self, url: &Url) -> PathBuf {
     self.location.join(url_to_filename(url))
   }
 
-  // TODO(bartlomieju): this method should check headers file
-  // and validate against ETAG/Last-modified-as headers.
-  // ETAG check is currently done in `cli/file_fetcher.rs`.
-  pub fn get(&self, url: &Url) -> Result<(File, HeadersMap), ErrBox> {
-    let cache_filename = self.location.join(url_to_filename(url));
-    let headers_filename = cache_filename.with_extension("headers.json");
-    let file = File::open(cache_filename)?;
+  /// `request_headers` are the headers of the request being considered
+  /// for cache reuse; they're only consulted when the cached response
+  /// carries a `Vary` header, to select the matching variant.
+  pub fn get(
+    &self,
+    url: &Url,
+    request_headers: &HeadersMap,
+  ) -> Result<(File, HeadersMap), ErrBox> {
+    let cache_filename = self.resolve_cache_filename(url, request_headers)?;
+    let headers_filename = headers_filename(&cache_filename);
+    let mut file = File::open(&cache_filename)?;
     let headers_json = fs::read_to_string(headers_filename)?;
     let headers_map: HeadersMap = serde_json::from_str(&headers_json)?;
+
+    // The digest sidecar predates neither `set()` nor this cache format,
+    // so its absence (e.g. an entry written before integrity checking
+    // existed) just means there's nothing to verify against.
+    if let Ok(expected_hex) =
+      fs::read_to_string(integrity_filename(&cache_filename))
+    {
+      let mut content = Vec::new();
+      file.read_to_end(&mut content)?;
+      if sha256_hex(&content) != expected_hex {
+        delete_cache_files(&cache_filename);
+        let _ = remove_variant_entry(&cache_filename);
+        self.remove_index_entry(&cache_filename)?;
+        return Err(
+          CorruptedCacheError {
+            url: url.to_owned(),
+          }
+          .into(),
+        );
+      }
+      file.seek(SeekFrom::Start(0))?;
+    }
+
+    self.record_access(&cache_filename)?;
     Ok((file, headers_map))
   }
 
+  // Removes the index entry for `cache_filename` (relative to
+  // `location`) and persists the index, so a file deleted outside of
+  // `evict_if_needed`/`prune`/`clear_variants` - e.g. a corrupted entry
+  // purged by `get()` - doesn't keep counting toward `total_bytes()`
+  // forever.
+  fn remove_index_entry(&self, cache_filename: &Path) -> Result<(), ErrBox> {
+    let rel_path = self.rel_path(cache_filename);
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let index = guard.as_mut().unwrap();
+    index.entries.remove(&rel_path);
+    let snapshot = index.clone();
+    drop(guard);
+    self.persist_index(&snapshot)
+  }
+
+  // Resolves the on-disk content filename for `url`, selecting among
+  // cached Vary variants (if any) using `request_headers`. Returns a
+  // `NotFound` error if the URL has variants but none match.
+  fn resolve_cache_filename(
+    &self,
+    url: &Url,
+    request_headers: &HeadersMap,
+  ) -> Result<PathBuf, ErrBox> {
+    let base_filename = self.location.join(url_to_filename(url));
+    let index_filename = base_filename.with_extension("variants.json");
+    if !index_filename.exists() {
+      return Ok(base_filename);
+    }
+
+    let index_json = fs::read_to_string(&index_filename)?;
+    let index: VariantIndex = serde_json::from_str(&index_json)?;
+    let matching = index.variants.iter().find(|variant| {
+      variant.selecting_headers.iter().all(|(name, expected)| {
+        let actual = request_headers
+          .iter()
+          .find(|(k, _)| k.to_lowercase() == *name)
+          .map(|(_, v)| v.trim().to_lowercase())
+          .unwrap_or_default();
+        actual == *expected
+      })
+    });
+
+    match matching {
+      Some(variant) => Ok(variant_filename(&base_filename, &variant.suffix)),
+      // `variants.json` can outlive the `Vary` response that created it -
+      // a later `set()` for the same URL without a `Vary` header writes
+      // straight to `base_filename` rather than rewriting every variant,
+      // so fall back to it here if it's actually on disk.
+      None if base_filename.exists() => Ok(base_filename),
+      None => Err(
+        std::io::Error::new(
+          std::io::ErrorKind::NotFound,
+          "no cached variant matches the request's Vary headers",
+        )
+        .into(),
+      ),
+    }
+  }
+
+  /// Computes the RFC 7234 freshness of the cached response for `url`,
+  /// relative to `request_time`. `request_time` should be the time the
+  /// caller is considering making a new request, and is used as "now"
+  /// for age calculations. The cached file's modification time stands
+  /// in for when the response was originally received (its
+  /// `response_time`).
+  ///
+  /// Returns `MustRevalidate` whenever the stored response carries
+  /// `Cache-Control: no-cache`/`no-store` (or `Vary: *`). A stale response
+  /// carrying `must-revalidate`/`proxy-revalidate` also returns
+  /// `MustRevalidate` instead of `Stale`; while it is still fresh it
+  /// returns `Fresh`.
+  pub fn get_cache_status(
+    &self,
+    url: &Url,
+    request_headers: &HeadersMap,
+    request_time: DateTime<Utc>,
+  ) -> Result<CacheStatus, ErrBox> {
+    let cache_filename = self.resolve_cache_filename(url, request_headers)?;
+    let headers_map = self.read_headers(url, request_headers)?;
+    let metadata = fs::metadata(&cache_filename)?;
+    let response_time: DateTime<Utc> = metadata.modified()?.into();
+
+    // RFC 7234 section 7.1.4 - `Vary: *` means the response varies on
+    // factors no header can capture, so it must never be served from
+    // cache without revalidation.
+    if header(&headers_map, "vary").map(|v| v.trim()) == Some("*") {
+      return Ok(CacheStatus::MustRevalidate);
+    }
+
+    let directives = cache_control_directives(&headers_map);
+    if directives.iter().any(|d| d == "no-cache" || d == "no-store") {
+      return Ok(CacheStatus::MustRevalidate);
+    }
+
+    let freshness_lifetime =
+      Self::freshness_lifetime(&headers_map, response_time);
+    let current_age =
+      Self::current_age(&headers_map, response_time, request_time);
+
+    if current_age < freshness_lifetime {
+      Ok(CacheStatus::Fresh)
+    } else if directives
+      .iter()
+      .any(|d| d == "must-revalidate" || d == "proxy-revalidate")
+    {
+      Ok(CacheStatus::MustRevalidate)
+    } else {
+      Ok(CacheStatus::Stale)
+    }
+  }
+
+  /// Returns the `ETag` and `Last-Modified` values stored for `url`, if
+  /// any, so that callers (e.g. `file_fetcher.rs`) can build a
+  /// conditional request when revalidating a stale entry.
+  pub fn get_validators(
+    &self,
+    url: &Url,
+    request_headers: &HeadersMap,
+  ) -> Result<(Option<String>, Option<String>), ErrBox> {
+    let headers_map = self.read_headers(url, request_headers)?;
+    Ok((
+      header(&headers_map, "etag").map(str::to_string),
+      header(&headers_map, "last-modified").map(str::to_string),
+    ))
+  }
+
+  fn read_headers(
+    &self,
+    url: &Url,
+    request_headers: &HeadersMap,
+  ) -> Result<HeadersMap, ErrBox> {
+    let cache_filename = self.resolve_cache_filename(url, request_headers)?;
+    let headers_filename = headers_filename(&cache_filename);
+    let headers_json = fs::read_to_string(headers_filename)?;
+    Ok(serde_json::from_str(&headers_json)?)
+  }
+
+  // RFC 7234 section 4.2.1 - prefers `max-age`, falls back to `Expires`
+  // minus `Date`, falls back to a heuristic fraction of `Date` minus
+  // `Last-Modified`, and finally to zero (always stale) if none apply.
+  fn freshness_lifetime(
+    headers_map: &HeadersMap,
+    response_time: DateTime<Utc>,
+  ) -> chrono::Duration {
+    if let Some(max_age) = cache_control_max_age(headers_map) {
+      return chrono::Duration::seconds(max_age.max(0));
+    }
+
+    let date = header(headers_map, "date")
+      .and_then(parse_http_date)
+      .unwrap_or(response_time);
+
+    if let Some(expires) = header(headers_map, "expires").and_then(parse_http_date)
+    {
+      return (expires - date).max(chrono::Duration::zero());
+    }
+
+    if let Some(last_modified) =
+      header(headers_map, "last-modified").and_then(parse_http_date)
+    {
+      let age = (date - last_modified).max(chrono::Duration::zero());
+      let heuristic_secs =
+        (age.num_seconds() as f64) * HEURISTIC_FRESHNESS_FRACTION;
+      return chrono::Duration::seconds(heuristic_secs as i64);
+    }
+
+    chrono::Duration::zero()
+  }
+
+  // RFC 7234 section 4.2.3 age calculation algorithm. We don't track
+  // request/response transit delay, so `age_value` (the stored `Age`
+  // header, if any) stands in for `corrected_age_value`.
+  fn current_age(
+    headers_map: &HeadersMap,
+    response_time: DateTime<Utc>,
+    now: DateTime<Utc>,
+  ) -> chrono::Duration {
+    let date = header(headers_map, "date")
+      .and_then(parse_http_date)
+      .unwrap_or(response_time);
+    let apparent_age =
+      (response_time - date).max(chrono::Duration::zero());
+
+    let age_value = header(headers_map, "age")
+      .and_then(|v| v.parse::<i64>().ok())
+      .map(chrono::Duration::seconds)
+      .unwrap_or_else(chrono::Duration::zero);
+
+    let corrected_initial_age = apparent_age.max(age_value);
+    let resident_time = (now - response_time).max(chrono::Duration::zero());
+    corrected_initial_age + resident_time
+  }
+
+  /// `request_headers` are the headers of the request that produced this
+  /// response; they're only consulted when `headers_map` carries a
+  /// `Vary` header, to record which variant this response is for.
   pub fn set(
     &self,
     url: &Url,
+    request_headers: &HeadersMap,
     headers_map: HeadersMap,
     content: &[u8],
   ) -> Result<(), ErrBox> {
-    let cache_filename = self.location.join(url_to_filename(url));
-    let headers_filename = cache_filename.with_extension("headers.json");
+    verify_server_integrity(url, &headers_map, content)?;
+
+    let base_filename = self.location.join(url_to_filename(url));
     // Create parent directory
-    let parent_filename = cache_filename
+    let parent_filename = base_filename
       .parent()
       .expect("Cache filename should have a parent dir");
     fs::create_dir_all(parent_filename)?;
-    // Cache content
-    deno_fs::write_file(&cache_filename, content, 0o666)?;
+
+    let vary_names = vary_header_names(&headers_map);
+    let cache_filename = if vary_names.is_empty() {
+      // The server has stopped (or never started) varying this response,
+      // so any previously-recorded variants are no longer reachable via
+      // `resolve_cache_filename`'s non-vary fallback - remove them rather
+      // than leaving them as orphaned files the index still tracks.
+      self.clear_variants(&base_filename)?;
+      base_filename
+    } else {
+      let selecting_headers =
+        select_vary_headers(&vary_names, request_headers);
+      let suffix = variant_suffix(&selecting_headers);
+      self.record_variant(&base_filename, selecting_headers, suffix.clone())?;
+      variant_filename(&base_filename, &suffix)
+    };
+
+    let headers_filename = headers_filename(&cache_filename);
+    // Cache content first - the headers (and integrity) files are only
+    // committed once the content they describe is durably on disk, so a
+    // reader never sees a headers file with no (or stale) matching
+    // content.
+    write_file_atomic(&cache_filename, content)?;
     let serialized_headers = serde_json::to_string(&headers_map)?;
     // Cache headers
-    deno_fs::write_file(&headers_filename, serialized_headers, 0o666)?;
+    write_file_atomic(&headers_filename, serialized_headers.as_bytes())?;
+    // Cache the body digest in its own sidecar, alongside the headers
+    // file, rather than inside `headers_map` - that map is handed back
+    // to callers verbatim by `get()`, so smuggling bookkeeping into it
+    // risks colliding with (and silently discarding) a real header the
+    // origin server sent.
+    write_file_atomic(
+      &integrity_filename(&cache_filename),
+      sha256_hex(content).as_bytes(),
+    )?;
+    self.touch_entry(&cache_filename, content.len() as u64)?;
+    self.evict_if_needed()?;
+    Ok(())
+  }
+
+  // Removes every variant recorded for `base_filename`'s URL, along with
+  // `variants.json` itself, so a subsequent non-vary `set()` doesn't leave
+  // `resolve_cache_filename` consulting a stale variant list.
+  fn clear_variants(&self, base_filename: &Path) -> Result<(), ErrBox> {
+    let index_filename = base_filename.with_extension("variants.json");
+    if !index_filename.exists() {
+      return Ok(());
+    }
+    let index: VariantIndex =
+      serde_json::from_str(&fs::read_to_string(&index_filename)?)?;
+
+    self.load_index()?;
+    let mut guard = self.index.lock().unwrap();
+    let cache_index = guard.as_mut().unwrap();
+    for variant in &index.variants {
+      let full_path = variant_filename(base_filename, &variant.suffix);
+      delete_cache_files(&full_path);
+      cache_index.entries.remove(&self.rel_path(&full_path));
+    }
+    drop(guard);
+
+    let _ = fs::remove_file(&index_filename);
+    Ok(())
+  }
+
+  // Records (or replaces) `suffix` as the on-disk variant selected by
+  // `selecting_headers` in the per-URL variant index.
+  fn record_variant(
+    &self,
+    base_filename: &Path,
+    selecting_headers: HeadersMap,
+    suffix: String,
+  ) -> Result<(), ErrBox> {
+    let index_filename = base_filename.with_extension("variants.json");
+    let mut index: VariantIndex = if index_filename.exists() {
+      serde_json::from_str(&fs::read_to_string(&index_filename)?)?
+    } else {
+      VariantIndex::default()
+    };
+    // Dedupe on the selecting headers themselves, so a repeated `set()`
+    // for the same logical variant replaces its row rather than adding
+    // a second one. `variant_suffix` derives the suffix from the same
+    // headers in a sorted, canonical form, so the replacement row points
+    // at the same file.
+    index
+      .variants
+      .retain(|v| v.selecting_headers != selecting_headers);
+    index.variants.push(Variant {
+      selecting_headers,
+      suffix,
+    });
+    let serialized_index = serde_json::to_string(&index)?;
+    write_file_atomic(&index_filename, serialized_index.as_bytes())?;
     Ok(())
   }
 }
@@ -130,7 +1013,7 @@ mod tests {
     let dir = TempDir::new().unwrap();
     let mut cache_path = dir.path().to_owned();
     cache_path.push("foobar");
-    let r = HttpCache::new(&cache_path);
+    let r = HttpCache::new(&cache_path, None);
     assert!(r.is_ok());
     assert!(cache_path.is_dir());
   }
@@ -138,7 +1021,7 @@ mod tests {
   #[test]
   fn test_get_set() {
     let dir = TempDir::new().unwrap();
-    let cache = HttpCache::new(dir.path()).unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
     let url = Url::parse("https://deno.land/x/welcome.ts").unwrap();
     let mut headers = HashMap::new();
     headers.insert(
@@ -147,10 +1030,10 @@ mod tests {
     );
     headers.insert("etag".to_string(), "as5625rqdsfb".to_string());
     let content = b"Hello world";
-    let r = cache.set(&url, headers, content);
+    let r = cache.set(&url, &HashMap::new(), headers, content);
     eprintln!("result {:?}", r);
     assert!(r.is_ok());
-    let r = cache.get(&url);
+    let r = cache.get(&url, &HashMap::new());
     assert!(r.is_ok());
     let (mut file, headers) = r.unwrap();
     let mut content = String::new();
@@ -165,6 +1048,307 @@ mod tests {
     drop(dir);
   }
 
+  #[test]
+  fn test_get_cache_status_max_age() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/fresh.ts").unwrap();
+    let mut headers = HashMap::new();
+    headers.insert("cache-control".to_string(), "max-age=3600".to_string());
+    cache.set(&url, &HashMap::new(), headers, b"fresh").unwrap();
+
+    let status = cache
+      .get_cache_status(&url, &HashMap::new(), Utc::now())
+      .unwrap();
+    assert_eq!(status, CacheStatus::Fresh);
+  }
+
+  #[test]
+  fn test_get_cache_status_no_cache() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/nocache.ts").unwrap();
+    let mut headers = HashMap::new();
+    headers.insert("cache-control".to_string(), "no-cache".to_string());
+    cache.set(&url, &HashMap::new(), headers, b"nocache").unwrap();
+
+    let status = cache
+      .get_cache_status(&url, &HashMap::new(), Utc::now())
+      .unwrap();
+    assert_eq!(status, CacheStatus::MustRevalidate);
+  }
+
+  #[test]
+  fn test_get_cache_status_vary_star_always_must_revalidate() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/varystar.ts").unwrap();
+    let mut headers = HashMap::new();
+    headers.insert("cache-control".to_string(), "max-age=3600".to_string());
+    headers.insert("vary".to_string(), "*".to_string());
+    cache.set(&url, &HashMap::new(), headers, b"varystar").unwrap();
+
+    // `max-age=3600` would otherwise make this `Fresh`, but `Vary: *`
+    // overrides that per RFC 7234 section 7.1.4.
+    let status = cache
+      .get_cache_status(&url, &HashMap::new(), Utc::now())
+      .unwrap();
+    assert_eq!(status, CacheStatus::MustRevalidate);
+  }
+
+  #[test]
+  fn test_get_cache_status_stale_without_max_age() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/stale.ts").unwrap();
+    let headers = HashMap::new();
+    cache.set(&url, &HashMap::new(), headers, b"stale").unwrap();
+
+    let status = cache
+      .get_cache_status(&url, &HashMap::new(), Utc::now())
+      .unwrap();
+    assert_eq!(status, CacheStatus::Stale);
+  }
+
+  #[test]
+  fn test_vary_stores_multiple_variants() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/negotiated.ts").unwrap();
+
+    let mut gzip_request = HashMap::new();
+    gzip_request
+      .insert("accept-encoding".to_string(), "gzip".to_string());
+    let mut gzip_response = HashMap::new();
+    gzip_response.insert("vary".to_string(), "Accept-Encoding".to_string());
+    cache
+      .set(&url, &gzip_request, gzip_response, b"gzip body")
+      .unwrap();
+
+    let mut br_request = HashMap::new();
+    br_request.insert("accept-encoding".to_string(), "br".to_string());
+    let mut br_response = HashMap::new();
+    br_response.insert("vary".to_string(), "Accept-Encoding".to_string());
+    cache
+      .set(&url, &br_request, br_response, b"br body")
+      .unwrap();
+
+    let (mut file, _) = cache.get(&url, &gzip_request).unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "gzip body");
+
+    let (mut file, _) = cache.get(&url, &br_request).unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "br body");
+
+    let mut deflate_request = HashMap::new();
+    deflate_request
+      .insert("accept-encoding".to_string(), "deflate".to_string());
+    assert!(cache.get(&url, &deflate_request).is_err());
+  }
+
+  #[test]
+  fn test_lru_eviction() {
+    let dir = TempDir::new().unwrap();
+    // Only two ten-byte entries fit at a time.
+    let cache = HttpCache::new(dir.path(), Some(20)).unwrap();
+
+    let url_a = Url::parse("https://deno.land/x/a.ts").unwrap();
+    cache
+      .set(&url_a, &HashMap::new(), HashMap::new(), b"0123456789")
+      .unwrap();
+    let url_b = Url::parse("https://deno.land/x/b.ts").unwrap();
+    cache
+      .set(&url_b, &HashMap::new(), HashMap::new(), b"0123456789")
+      .unwrap();
+    // Refresh `url_a` so `url_b` becomes the least-recently-used entry.
+    cache.get(&url_a, &HashMap::new()).unwrap();
+
+    let url_c = Url::parse("https://deno.land/x/c.ts").unwrap();
+    cache
+      .set(&url_c, &HashMap::new(), HashMap::new(), b"0123456789")
+      .unwrap();
+
+    // `url_b` is the least-recently-used entry once `url_c` pushes the
+    // cache over its 20-byte budget, so it's the one evicted.
+    assert!(cache.get(&url_a, &HashMap::new()).is_ok());
+    assert!(cache.get(&url_b, &HashMap::new()).is_err());
+    assert!(cache.get(&url_c, &HashMap::new()).is_ok());
+  }
+
+  #[test]
+  fn test_eviction_cleans_up_variant_index() {
+    let dir = TempDir::new().unwrap();
+    // Only one ten-byte variant fits at a time.
+    let cache = HttpCache::new(dir.path(), Some(10)).unwrap();
+    let url = Url::parse("https://deno.land/x/negotiated.ts").unwrap();
+
+    let mut gzip_request = HashMap::new();
+    gzip_request
+      .insert("accept-encoding".to_string(), "gzip".to_string());
+    let mut gzip_response = HashMap::new();
+    gzip_response.insert("vary".to_string(), "Accept-Encoding".to_string());
+    cache
+      .set(&url, &gzip_request, gzip_response, b"0123456789")
+      .unwrap();
+
+    let mut br_request = HashMap::new();
+    br_request.insert("accept-encoding".to_string(), "br".to_string());
+    let mut br_response = HashMap::new();
+    br_response.insert("vary".to_string(), "Accept-Encoding".to_string());
+    cache
+      .set(&url, &br_request, br_response, b"9876543210")
+      .unwrap();
+
+    // The gzip variant should have been evicted to stay under budget,
+    // and its (now dangling) row removed from the variants sidecar -
+    // not just its content file deleted out from under the index.
+    assert!(cache.get(&url, &gzip_request).is_err());
+    assert!(cache.get(&url, &br_request).is_ok());
+
+    let index_filename = cache
+      .get_cache_filename(&url)
+      .with_extension("variants.json");
+    let index: VariantIndex =
+      serde_json::from_str(&fs::read_to_string(&index_filename).unwrap())
+        .unwrap();
+    assert_eq!(index.variants.len(), 1);
+    assert_eq!(
+      index.variants[0].selecting_headers.get("accept-encoding"),
+      Some(&"br".to_string())
+    );
+  }
+
+  #[test]
+  fn test_prune_removes_stale_entries() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/old.ts").unwrap();
+
+    let mut request_headers = HashMap::new();
+    request_headers
+      .insert("accept-encoding".to_string(), "gzip".to_string());
+    let mut response_headers = HashMap::new();
+    response_headers.insert("vary".to_string(), "Accept-Encoding".to_string());
+    cache
+      .set(&url, &request_headers, response_headers, b"old content")
+      .unwrap();
+
+    // There's no way to fake the system clock, so age the entry by
+    // rewriting its on-disk `accessed` time directly, then reload it
+    // from a fresh instance the way a real process restart would.
+    let index_filename = dir.path().join("cache_index.json");
+    let mut index: CacheIndex =
+      serde_json::from_str(&fs::read_to_string(&index_filename).unwrap())
+        .unwrap();
+    for entry in index.entries.values_mut() {
+      entry.accessed -= chrono::Duration::days(2).num_milliseconds();
+    }
+    fs::write(&index_filename, serde_json::to_string(&index).unwrap())
+      .unwrap();
+
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    cache.prune(chrono::Duration::days(1)).unwrap();
+
+    assert!(cache.get(&url, &request_headers).is_err());
+    let variants_filename =
+      cache.get_cache_filename(&url).with_extension("variants.json");
+    assert!(!variants_filename.exists());
+
+    let index: CacheIndex =
+      serde_json::from_str(&fs::read_to_string(&index_filename).unwrap())
+        .unwrap();
+    assert!(index.entries.is_empty());
+  }
+
+  #[test]
+  fn test_flush_index_persists_access_time_across_restart() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/hot.ts").unwrap();
+    cache
+      .set(&url, &HashMap::new(), HashMap::new(), b"hot")
+      .unwrap();
+
+    let index_filename = dir.path().join("cache_index.json");
+    let before: CacheIndex =
+      serde_json::from_str(&fs::read_to_string(&index_filename).unwrap())
+        .unwrap();
+    let rel_path = before.entries.keys().next().unwrap().clone();
+    let set_accessed = before.entries[&rel_path].accessed;
+
+    // `get()` only bumps the in-memory access time - `flush_index()`
+    // forces it to disk immediately, bypassing the usual throttle.
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    cache.get(&url, &HashMap::new()).unwrap();
+    cache.flush_index().unwrap();
+
+    // A fresh instance over the same directory, simulating a process
+    // restart, must load the bumped access time rather than the one
+    // `set()` recorded.
+    let restarted = HttpCache::new(dir.path(), None).unwrap();
+    restarted.load_index().unwrap();
+    let guard = restarted.index.lock().unwrap();
+    let get_accessed = guard.as_ref().unwrap().entries[&rel_path].accessed;
+    assert!(get_accessed > set_accessed);
+  }
+
+  #[test]
+  fn test_clear() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/cleared.ts").unwrap();
+    cache
+      .set(&url, &HashMap::new(), HashMap::new(), b"content")
+      .unwrap();
+    assert!(cache.get(&url, &HashMap::new()).is_ok());
+
+    cache.clear().unwrap();
+    assert!(cache.get(&url, &HashMap::new()).is_err());
+  }
+
+  #[test]
+  fn test_corrupted_cache_is_detected_and_purged() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/corrupt.ts").unwrap();
+    cache
+      .set(&url, &HashMap::new(), HashMap::new(), b"original")
+      .unwrap();
+
+    // Simulate disk corruption / a truncated write.
+    let cache_filename = cache.get_cache_filename(&url);
+    fs::write(&cache_filename, b"tampered").unwrap();
+
+    let err = cache.get(&url, &HashMap::new()).unwrap_err();
+    assert!(err.to_string().contains("integrity"));
+    // The corrupted entry is removed so a subsequent fetch can re-cache.
+    assert!(!cache_filename.exists());
+  }
+
+  #[test]
+  fn test_integrity_digest_does_not_collide_with_real_header() {
+    let dir = TempDir::new().unwrap();
+    let cache = HttpCache::new(dir.path(), None).unwrap();
+    let url = Url::parse("https://deno.land/x/real_header.ts").unwrap();
+    let mut headers = HashMap::new();
+    // An origin is free to send a header under the same name our
+    // bookkeeping used to smuggle the digest into; it must come back
+    // untouched rather than being clobbered or stripped.
+    headers.insert("x-deno-integrity".to_string(), "from-origin".to_string());
+    cache
+      .set(&url, &HashMap::new(), headers, b"hello")
+      .unwrap();
+
+    let (_, headers) = cache.get(&url, &HashMap::new()).unwrap();
+    assert_eq!(
+      headers.get("x-deno-integrity").unwrap(),
+      "from-origin"
+    );
+  }
+
   #[test]
   fn test_url_to_filename() {
     let test_cases = [